@@ -1,29 +1,213 @@
+use std::fmt;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
 use super::{
     movement, offset_for_delete_backwards, BasicTextInput, EditAction, EditableText, MouseAction,
     Movement, Selection, TextInput, TextLayout, TextStorage,
 };
 use crate::kurbo::Line;
 use crate::piet::PietText;
-use crate::{Application, Env, KeyEvent, MouseEvent, PaintCtx, Point, Rect, UpdateCtx};
+use crate::{
+    Application, Env, KbKey, KeyEvent, Modifiers, MouseEvent, PaintCtx, Point, Rect, UpdateCtx,
+};
+
+/// The maximum gap between two edits for them to be coalesced into the
+/// same undo transaction.
+const UNDO_GROUP_TIMEOUT: Duration = Duration::from_millis(750);
+
+/// A single contiguous edit to the document, recorded so it can be reversed.
+#[derive(Debug, Clone)]
+struct Change {
+    range: Range<usize>,
+    old_text: String,
+    new_text: String,
+}
 
+/// A group of [`Change`]s that are undone or redone together.
 #[derive(Debug, Clone)]
+struct Transaction {
+    changes: Vec<Change>,
+    selections_before: SelectionSet,
+    selections_after: SelectionSet,
+}
+
+/// The kind of edit that can be coalesced with a following edit of the same kind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GroupKind {
+    Insert,
+    Backspace,
+}
+
+/// The unit a click/drag selects, determined by how many successive clicks
+/// landed near the same spot: single click selects a character (caret),
+/// double-click a word, triple-click (and beyond) a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Granularity {
+    Char,
+    Word,
+    Line,
+}
+
+impl Granularity {
+    fn from_click_count(count: u32) -> Granularity {
+        match count {
+            0 | 1 => Granularity::Char,
+            2 => Granularity::Word,
+            _ => Granularity::Line,
+        }
+    }
+}
+
+/// A sorted set of disjoint [`Selection`]s, one of which is distinguished as
+/// the `primary` (the one mouse clicks and single-cursor queries act on).
+///
+/// This is what lets `Editor` support multiple simultaneous cursors: every
+/// edit and movement is fanned out over all ranges, and overlapping ranges
+/// are merged back together after each operation.
+#[derive(Debug, Clone)]
+struct SelectionSet {
+    ranges: Vec<Selection>,
+    /// Per-selection cached x position for goal-column affinity during a
+    /// streak of vertical movement, parallel to `ranges`. Cleared for a
+    /// selection by any edit, click, or non-vertical movement of that
+    /// selection; merged selections lose their affinity since neither side's
+    /// x position is necessarily still meaningful.
+    affinities: Vec<Option<f64>>,
+    primary: usize,
+}
+
+impl SelectionSet {
+    fn single(selection: Selection) -> Self {
+        SelectionSet {
+            ranges: vec![selection],
+            affinities: vec![None],
+            primary: 0,
+        }
+    }
+
+    fn primary(&self) -> Selection {
+        self.ranges[self.primary]
+    }
+
+    fn set_single(&mut self, selection: Selection) {
+        self.ranges = vec![selection];
+        self.affinities = vec![None];
+        self.primary = 0;
+    }
+
+    /// Append a new selection (with no cached affinity), without normalizing.
+    fn push(&mut self, selection: Selection) {
+        self.ranges.push(selection);
+        self.affinities.push(None);
+    }
+
+    /// Clear the cached goal-column affinity for every selection, e.g. on a
+    /// horizontal movement, edit, click, or drag.
+    fn clear_affinities(&mut self) {
+        for affinity in self.affinities.iter_mut() {
+            *affinity = None;
+        }
+    }
+
+    fn constrain_to(&mut self, data: &impl EditableText) {
+        for sel in self.ranges.iter_mut() {
+            sel.constrain_to(data);
+        }
+        self.normalize();
+    }
+
+    /// Re-sort the ranges and merge any that now overlap, keeping `primary`
+    /// pointed at the range that contains (or is closest to) the old primary
+    /// caret.
+    fn normalize(&mut self) {
+        if self.ranges.len() <= 1 {
+            return;
+        }
+        let primary_caret = self.ranges[self.primary].end;
+
+        let mut paired: Vec<(Selection, Option<f64>)> = self
+            .ranges
+            .iter()
+            .copied()
+            .zip(self.affinities.iter().copied())
+            .collect();
+        paired.sort_by_key(|(sel, _)| sel.min());
+
+        let mut merged: Vec<(Selection, Option<f64>)> = Vec::with_capacity(paired.len());
+        for (sel, affinity) in paired {
+            match merged.last_mut() {
+                Some((last, last_affinity)) if sel.min() <= last.max() => {
+                    *last = Selection::new(last.min().min(sel.min()), last.max().max(sel.max()));
+                    *last_affinity = None;
+                }
+                _ => merged.push((sel, affinity)),
+            }
+        }
+
+        self.primary = merged
+            .iter()
+            .position(|(sel, _)| sel.min() <= primary_caret && primary_caret <= sel.max())
+            .unwrap_or(merged.len() - 1);
+        self.ranges = merged.iter().map(|(sel, _)| *sel).collect();
+        self.affinities = merged.into_iter().map(|(_, affinity)| affinity).collect();
+    }
+}
+
 pub struct Editor<T> {
     layout: TextLayout<T>,
-    selection: Selection,
+    selections: SelectionSet,
     multi_line: bool,
     fixed_width: f64,
-    // this can be Box<dyn TextInput> in the future
-    editor: BasicTextInput,
+    editor: Box<dyn TextInput>,
+    undo: Vec<Transaction>,
+    redo: Vec<Transaction>,
+    group_kind: Option<GroupKind>,
+    last_edit_at: Option<Instant>,
+    /// The granularity and range the in-progress click/drag is anchored to.
+    click_anchor: Option<(Granularity, Range<usize>)>,
+    search: Option<SearchQuery>,
+    matches: Vec<Range<usize>>,
+    matches_stale: bool,
+    active_match: Option<usize>,
+}
+
+impl<T: fmt::Debug> fmt::Debug for Editor<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Editor")
+            .field("layout", &self.layout)
+            .field("selections", &self.selections)
+            .field("multi_line", &self.multi_line)
+            .field("fixed_width", &self.fixed_width)
+            .field("editor", &"Box<dyn TextInput>")
+            .field("undo", &self.undo)
+            .field("redo", &self.redo)
+            .field("search", &self.search)
+            .field("matches", &self.matches)
+            .field("group_kind", &self.group_kind)
+            .field("last_edit_at", &self.last_edit_at)
+            .field("click_anchor", &self.click_anchor)
+            .finish()
+    }
 }
 
 impl<T> Editor<T> {
     pub fn new() -> Self {
         Editor {
             layout: TextLayout::new(),
-            selection: Selection::caret(0),
+            selections: SelectionSet::single(Selection::caret(0)),
             multi_line: false,
             fixed_width: f64::INFINITY,
-            editor: BasicTextInput::default(),
+            editor: Box::new(BasicTextInput::default()),
+            undo: Vec::new(),
+            redo: Vec::new(),
+            group_kind: None,
+            last_edit_at: None,
+            click_anchor: None,
+            search: None,
+            matches: Vec::new(),
+            matches_stale: false,
+            active_match: None,
         }
     }
 
@@ -32,6 +216,14 @@ impl<T> Editor<T> {
         self
     }
 
+    /// Replace the default key handling with a custom [`TextInput`]
+    /// implementation, e.g. a [`KeymapInput`] with application-specific
+    /// bindings or a modal (vi-style) input.
+    pub fn with_input(mut self, input: impl TextInput + 'static) -> Self {
+        self.editor = Box::new(input);
+        self
+    }
+
     pub fn set_wrap_width(&mut self, width: f64) {
         self.layout.set_wrap_width(width);
     }
@@ -39,36 +231,100 @@ impl<T> Editor<T> {
 
 impl<T: TextStorage + EditableText> Editor<T> {
     pub fn set_text(&mut self, text: T) {
-        self.layout.set_text(text)
+        self.layout.set_text(text);
+        self.matches_stale = true;
     }
 
+    /// The primary selection: the one a plain click or a single-cursor query acts on.
     pub fn selection(&self) -> &Selection {
-        &self.selection
+        &self.selections.ranges[self.selections.primary]
+    }
+
+    /// All active selections/carets, in document order; the primary one is
+    /// not necessarily first.
+    pub fn selections(&self) -> Vec<Selection> {
+        self.selections.ranges.clone()
     }
 
     pub fn selection_rects(&self) -> Vec<Rect> {
-        self.layout.rects_for_range(self.selection.range())
+        self.selections
+            .ranges
+            .iter()
+            .flat_map(|sel| self.layout.rects_for_range(sel.range()))
+            .collect()
     }
 
-    pub fn cursor_line(&self) -> Line {
-        self.layout
-            .cursor_line_for_text_position(self.selection.end)
+    /// One caret line per active cursor.
+    pub fn cursor_lines(&self) -> Vec<Line> {
+        self.selections
+            .ranges
+            .iter()
+            .map(|sel| self.layout.cursor_line_for_text_position(sel.end))
+            .collect()
     }
 
-    pub fn click(&mut self, mouse: &MouseEvent, data: &mut T) {
-        self.do_edit(EditAction::Click(self.mouse_action_for_event(mouse)), data);
+    /// Handle a mouse-down. `click_count` is 1 for a plain click, 2 for a
+    /// double-click, 3 (and above) for a triple-click, as tracked by the
+    /// caller from the timing and position of successive mouse-downs.
+    pub fn click(&mut self, mouse: &MouseEvent, click_count: u32, data: &mut T) {
+        self.do_edit(
+            EditAction::Click(self.mouse_action_for_event(mouse, click_count)),
+            data,
+        );
     }
 
     pub fn drag(&mut self, mouse: &MouseEvent, data: &mut T) {
-        self.do_edit(EditAction::Drag(self.mouse_action_for_event(mouse)), data);
+        self.do_edit(EditAction::Drag(self.mouse_action_for_event(mouse, 1)), data);
     }
 
-    fn mouse_action_for_event(&self, event: &MouseEvent) -> MouseAction {
+    fn mouse_action_for_event(&self, event: &MouseEvent, click_count: u32) -> MouseAction {
         let pos = self.layout.text_position_for_point(event.pos);
         MouseAction {
             row: 0,
             column: pos,
             mods: event.mods,
+            click_count,
+        }
+    }
+
+    /// The range a click/drag at `pos` should select at the given granularity.
+    fn granular_range(&self, pos: usize, granularity: Granularity, data: &T) -> Range<usize> {
+        match granularity {
+            Granularity::Char => pos..pos,
+            Granularity::Word => {
+                let start = movement(Movement::LeftWord, Selection::caret(pos), data, false).end;
+                let end = movement(Movement::RightWord, Selection::caret(start), data, false).end;
+                start..end
+            }
+            Granularity::Line => {
+                let start = movement(Movement::LineStart, Selection::caret(pos), data, false).end;
+                let end = movement(Movement::LineEnd, Selection::caret(pos), data, false).end;
+                start..end
+            }
+        }
+    }
+
+    /// Extend the primary selection's active end to `pos`, snapping to the
+    /// granularity the drag's originating click was anchored at.
+    fn drag_to(&mut self, pos: usize, data: &T) {
+        let new_sel = match self.click_anchor.clone() {
+            Some((granularity, anchor)) => {
+                let hover = self.granular_range(pos, granularity, data);
+                if pos < anchor.start {
+                    Selection::new(anchor.end, hover.start)
+                } else {
+                    Selection::new(anchor.start, hover.end)
+                }
+            }
+            None => {
+                let mut sel = self.selections.primary();
+                sel.end = pos;
+                sel
+            }
+        };
+        let primary = self.selections.primary;
+        if let Some(sel) = self.selections.ranges.get_mut(primary) {
+            *sel = new_sel;
         }
     }
 
@@ -78,10 +334,49 @@ impl<T: TextStorage + EditableText> Editor<T> {
         }
     }
 
+    /// The current mode of the input, if it implements one (currently only
+    /// [`ModalInput`] does). Lets `TextBox2` choose between a block caret in
+    /// normal/visual mode and a bar caret in insert mode.
+    pub fn modal_mode(&self) -> Option<InputMode> {
+        self.editor
+            .as_any()
+            .downcast_ref::<ModalInput>()
+            .map(|modal| modal.mode())
+    }
+
+    /// A filled rect covering the grapheme at each caret, for rendering a
+    /// block caret in modal editing's normal/visual modes. Carets at or past
+    /// the end of the text (including on an empty buffer) fall back to
+    /// covering the previous grapheme, or render nothing if there is none.
+    pub fn cursor_blocks(&self) -> Vec<Rect> {
+        let text = match self.layout.text() {
+            Some(text) => text,
+            None => return Vec::new(),
+        };
+        let len = text.len();
+        if len == 0 {
+            return Vec::new();
+        }
+        self.selections
+            .ranges
+            .iter()
+            .flat_map(|sel| {
+                let end = sel.end.min(len);
+                let (start, end) = if end < len {
+                    (end, text.next_grapheme_offset(end).unwrap_or(len))
+                } else {
+                    (text.prev_grapheme_offset(end).unwrap_or(0), end)
+                };
+                self.layout.rects_for_range(start..end)
+            })
+            .collect()
+    }
+
     pub fn update(&mut self, ctx: &mut UpdateCtx, new_data: &T, env: &Env) {
         if self.data_is_stale(new_data) {
             self.layout.set_text(new_data.clone());
-            self.selection.constrain_to(new_data);
+            self.selections.constrain_to(new_data);
+            self.matches_stale = true;
             ctx.request_paint();
         } else if self.layout.needs_rebuild_after_update(ctx) {
             ctx.request_paint();
@@ -100,30 +395,82 @@ impl<T: TextStorage + EditableText> Editor<T> {
             return;
         }
         match edit {
-            EditAction::Insert(chars) | EditAction::Paste(chars) => self.insert(&chars, data),
+            EditAction::Insert(chars) => self.insert(&chars, data, false),
+            EditAction::Paste(chars) => self.insert(&chars, data, true),
             EditAction::Backspace => self.delete_backward(data),
             EditAction::Delete => self.delete_forward(data),
             EditAction::JumpDelete(mvmt) | EditAction::JumpBackspace(mvmt) => {
-                let to_delete = if self.selection.is_caret() {
-                    movement(mvmt, self.selection, data, true)
-                } else {
-                    self.selection
-                };
-                data.edit(to_delete.range(), "");
-                self.selection = Selection::caret(to_delete.min());
+                self.edit_all(data, None, |data, sel| {
+                    let to_delete = if sel.is_caret() {
+                        movement(mvmt, sel, data, true)
+                    } else {
+                        sel
+                    };
+                    (to_delete.range(), String::new())
+                });
+            }
+            EditAction::Move(mvmt) => {
+                self.move_all(data, mvmt, false);
+                self.break_undo_group();
             }
-            EditAction::Move(mvmt) => self.selection = movement(mvmt, self.selection, data, false),
             EditAction::ModifySelection(mvmt) => {
-                self.selection = movement(mvmt, self.selection, data, true)
+                self.move_all(data, mvmt, true);
+                self.break_undo_group();
             }
             EditAction::Click(action) => {
+                let granularity = Granularity::from_click_count(action.click_count);
                 if action.mods.shift() {
-                    self.selection.end = action.column;
+                    let mut sel = self.selections.primary();
+                    sel.end = action.column;
+                    self.selections.set_single(sel);
+                    self.click_anchor = None;
                 } else {
-                    self.selection = Selection::caret(action.column);
+                    let range = self.granular_range(action.column, granularity, data);
+                    let sel = match granularity {
+                        Granularity::Char => Selection::caret(action.column),
+                        Granularity::Word | Granularity::Line => {
+                            Selection::new(range.start, range.end)
+                        }
+                    };
+                    self.selections.set_single(sel);
+                    self.click_anchor = Some((granularity, range));
+                }
+                self.selections.clear_affinities();
+                self.break_undo_group();
+            }
+            EditAction::Drag(action) => {
+                self.drag_to(action.column, data);
+                self.selections.clear_affinities();
+                self.break_undo_group();
+            }
+            EditAction::AddCaretAbove => {
+                self.add_caret_vertical(false);
+                self.break_undo_group();
+            }
+            EditAction::AddCaretBelow => {
+                self.add_caret_vertical(true);
+                self.break_undo_group();
+            }
+            EditAction::AddCaretAtNextOccurrence => {
+                self.add_caret_at_next_occurrence(data);
+                self.break_undo_group();
+            }
+            EditAction::Yank(mvmt) => {
+                // select the range the motion covers, copy it, then restore
+                // the caret: a non-destructive counterpart to JumpDelete.
+                let before = self.selections.primary();
+                let primary = self.selections.primary;
+                if let Some(sel) = self.selections.ranges.get_mut(primary) {
+                    *sel = movement(mvmt, before, data, true);
+                }
+                self.set_clipboard();
+                if let Some(sel) = self.selections.ranges.get_mut(primary) {
+                    *sel = before;
                 }
+                self.break_undo_group();
             }
-            EditAction::Drag(action) => self.selection.end = action.column,
+            EditAction::Undo => self.undo(data),
+            EditAction::Redo => self.redo(data),
             _ => (),
         }
     }
@@ -142,43 +489,267 @@ impl<T: TextStorage + EditableText> Editor<T> {
         self.layout.text().map(|t| !t.same(data)).unwrap_or(true)
     }
 
-    fn insert(&mut self, text: &str, data: &mut T) {
+    /// Move (or extend, if `modify`) every selection by `mvmt`.
+    ///
+    /// `Up`/`Down` are handled specially, and per selection: the first
+    /// vertical move in a streak caches that caret's current x position in
+    /// `selections.affinities`, and every subsequent vertical move in that
+    /// streak is resolved against that same x rather than the (possibly
+    /// shorter) target line's own width. Each selection's affinity is
+    /// independent, so carets that aren't column-aligned keep their own goal
+    /// column instead of snapping to the primary caret's.
+    fn move_all(&mut self, data: &T, mvmt: Movement, modify: bool) {
+        match mvmt {
+            Movement::Up | Movement::Down => {
+                let down = matches!(mvmt, Movement::Down);
+                for i in 0..self.selections.ranges.len() {
+                    let sel = self.selections.ranges[i];
+                    let x = match self.selections.affinities[i] {
+                        Some(x) => x,
+                        None => {
+                            let x = self.layout.cursor_line_for_text_position(sel.end).p0.x;
+                            self.selections.affinities[i] = Some(x);
+                            x
+                        }
+                    };
+                    let line = self.layout.cursor_line_for_text_position(sel.end);
+                    let line_height = (line.p1.y - line.p0.y).max(1.0);
+                    let y = if down {
+                        line.p1.y + line_height * 0.5
+                    } else {
+                        line.p0.y - line_height * 0.5
+                    };
+                    let pos = self.layout.text_position_for_point(Point::new(x, y));
+                    let sel = &mut self.selections.ranges[i];
+                    if modify {
+                        sel.end = pos;
+                    } else {
+                        *sel = Selection::caret(pos);
+                    }
+                }
+            }
+            _ => {
+                self.selections.clear_affinities();
+                for sel in self.selections.ranges.iter_mut() {
+                    *sel = movement(mvmt, *sel, data, modify);
+                }
+            }
+        }
+        self.selections.normalize();
+    }
+
+    /// Apply `compute` to every active selection, in position order, shifting
+    /// each later selection's offsets by the net length delta of the edits
+    /// applied before it. `compute` receives the (already shifted) selection
+    /// and returns the range to replace and the text to replace it with; the
+    /// resulting caret for that selection is placed at the end of the
+    /// replacement text.
+    fn edit_all<F>(&mut self, data: &mut T, coalesce: Option<GroupKind>, mut compute: F)
+    where
+        F: FnMut(&mut T, Selection) -> (Range<usize>, String),
+    {
+        let selections_before = self.selections.clone();
+        let mut order: Vec<usize> = (0..selections_before.ranges.len()).collect();
+        order.sort_by_key(|&i| selections_before.ranges[i].min());
+
+        let mut delta: isize = 0;
+        let mut new_ranges = selections_before.ranges.clone();
+        let mut changes = Vec::with_capacity(order.len());
+
+        for i in order {
+            let mut sel = selections_before.ranges[i];
+            sel.start = (sel.start as isize + delta).max(0) as usize;
+            sel.end = (sel.end as isize + delta).max(0) as usize;
+
+            let (range, new_text) = compute(data, sel);
+            let old_text = data
+                .slice(range.clone())
+                .map(|s| s.into_owned())
+                .unwrap_or_default();
+            data.edit(range.clone(), &new_text);
+
+            new_ranges[i] = Selection::caret(range.start + new_text.len());
+            delta += new_text.len() as isize - (range.end - range.start) as isize;
+            changes.push(Change {
+                range,
+                old_text,
+                new_text,
+            });
+        }
+
+        self.selections.ranges = new_ranges;
+        self.selections.normalize();
+        self.selections.clear_affinities();
+        self.matches_stale = true;
+        self.record_changes(changes, selections_before, coalesce);
+    }
+
+    /// Push `changes` as a single transaction, coalescing it into the current
+    /// transaction if `coalesce` matches the kind of the transaction in
+    /// progress, there is a single caret involved, and the group hasn't timed out.
+    fn record_changes(
+        &mut self,
+        changes: Vec<Change>,
+        selections_before: SelectionSet,
+        coalesce: Option<GroupKind>,
+    ) {
+        if changes.is_empty() {
+            return;
+        }
+        self.redo.clear();
+        let now = Instant::now();
+        let continues_group = coalesce.is_some()
+            && coalesce == self.group_kind
+            && selections_before.ranges.len() == 1
+            && self
+                .last_edit_at
+                .map(|at| now.duration_since(at) < UNDO_GROUP_TIMEOUT)
+                .unwrap_or(false);
+
+        if continues_group {
+            if let Some(txn) = self.undo.last_mut() {
+                txn.changes.extend(changes);
+                txn.selections_after = self.selections.clone();
+                self.group_kind = coalesce;
+                self.last_edit_at = Some(now);
+                return;
+            }
+        }
+
+        self.undo.push(Transaction {
+            changes,
+            selections_before,
+            selections_after: self.selections.clone(),
+        });
+        self.group_kind = coalesce;
+        self.last_edit_at = Some(now);
+    }
+
+    /// End the current coalescing group, so the next edit starts a new transaction.
+    fn break_undo_group(&mut self) {
+        self.group_kind = None;
+        self.last_edit_at = None;
+    }
+
+    /// Undo the most recent transaction, if any.
+    pub fn undo(&mut self, data: &mut T) {
+        if let Some(txn) = self.undo.pop() {
+            for change in txn.changes.iter().rev() {
+                let end = change.range.start + change.new_text.len();
+                data.edit(change.range.start..end, &change.old_text);
+            }
+            self.selections = txn.selections_before.clone();
+            self.matches_stale = true;
+            self.redo.push(txn);
+            self.break_undo_group();
+        }
+    }
+
+    /// Redo the most recently undone transaction, if any.
+    pub fn redo(&mut self, data: &mut T) {
+        if let Some(txn) = self.redo.pop() {
+            for change in txn.changes.iter() {
+                data.edit(change.range.clone(), &change.new_text);
+            }
+            self.selections = txn.selections_after.clone();
+            self.matches_stale = true;
+            self.undo.push(txn);
+            self.break_undo_group();
+        }
+    }
+
+    /// Inserts `text` at every selection. `is_paste` marks the edit as a
+    /// paste rather than typing, which always breaks the undo-coalescing
+    /// group: a pasted single character shouldn't merge into whatever typing
+    /// happened to precede it within the coalescing window.
+    fn insert(&mut self, text: &str, data: &mut T, is_paste: bool) {
         // if we aren't multiline, we insert only up to the first newline
         let text = if self.multi_line {
             text
         } else {
             text.split('\n').next().unwrap_or("")
         };
-        let sel = self.selection.range();
-        data.edit(sel, text);
-        self.selection = Selection::caret(self.selection.min() + text.len());
+        let coalesce = (!is_paste
+            && self.selections.ranges.len() == 1
+            && self.selections.primary().is_caret()
+            && text.chars().count() == 1)
+            .then(|| GroupKind::Insert);
+        let text = text.to_owned();
+        self.edit_all(data, coalesce, move |_, sel| (sel.range(), text.clone()));
     }
 
     /// Delete to previous grapheme if in caret mode.
     /// Otherwise just delete everything inside the selection.
     fn delete_backward(&mut self, data: &mut T) {
-        let cursor_pos = if self.selection.is_caret() {
-            let del_end = self.selection.end;
-            let del_start = offset_for_delete_backwards(&self.selection, data);
-            data.edit(del_start..del_end, "");
-            del_start
-        } else {
-            data.edit(self.selection.range(), "");
-            self.selection.min()
-        };
-
-        self.selection = Selection::caret(cursor_pos);
+        let coalesce = (self.selections.ranges.len() == 1 && self.selections.primary().is_caret())
+            .then(|| GroupKind::Backspace);
+        self.edit_all(data, coalesce, |data, sel| {
+            if sel.is_caret() {
+                let del_start = offset_for_delete_backwards(&sel, data);
+                (del_start..sel.end, String::new())
+            } else {
+                (sel.range(), String::new())
+            }
+        });
     }
 
     fn delete_forward(&mut self, data: &mut T) {
-        let to_delete = if self.selection.is_caret() {
-            movement(Movement::Right, self.selection, data, false)
-        } else {
-            self.selection
+        self.edit_all(data, None, |data, sel| {
+            let to_delete = if sel.is_caret() {
+                movement(Movement::Right, sel, data, false)
+            } else {
+                sel
+            };
+            (to_delete.range(), String::new())
+        });
+    }
+
+    /// Add a new caret on the line above (`down == false`) or below
+    /// (`down == true`) every existing caret, at the same x position.
+    fn add_caret_vertical(&mut self, down: bool) {
+        let mut added = Vec::with_capacity(self.selections.ranges.len());
+        for sel in self.selections.ranges.iter() {
+            let line = self.layout.cursor_line_for_text_position(sel.end);
+            let line_height = (line.p1.y - line.p0.y).max(1.0);
+            let y = if down {
+                line.p1.y + line_height * 0.5
+            } else {
+                line.p0.y - line_height * 0.5
+            };
+            let pos = self
+                .layout
+                .text_position_for_point(Point::new(line.p0.x, y));
+            added.push(Selection::caret(pos));
+        }
+        for sel in added {
+            self.selections.push(sel);
+        }
+        self.selections.normalize();
+    }
+
+    /// Add a new selection at the next occurrence of the primary selection's
+    /// text, wrapping around to the start of the document if necessary.
+    fn add_caret_at_next_occurrence(&mut self, data: &T) {
+        let primary = self.selections.primary();
+        if primary.is_caret() {
+            return;
+        }
+        let needle = match data.slice(primary.range()) {
+            Some(s) if !s.is_empty() => s.into_owned(),
+            _ => return,
         };
+        let haystack = data.as_str();
+        let found = haystack[primary.max()..]
+            .find(needle.as_str())
+            .map(|i| i + primary.max())
+            .or_else(|| haystack.find(needle.as_str()));
 
-        data.edit(to_delete.range(), "");
-        self.selection = Selection::caret(self.selection.min());
+        if let Some(start) = found {
+            let new_sel = Selection::new(start, start + needle.len());
+            self.selections.push(new_sel);
+            self.selections.primary = self.selections.ranges.len() - 1;
+            self.selections.normalize();
+        }
     }
 
     pub fn copy(&self, data: &mut T) {
@@ -198,7 +769,7 @@ impl<T: TextStorage + EditableText> Editor<T> {
         if let Some(text) = self
             .layout
             .text()
-            .and_then(|txt| txt.slice(self.selection.range()))
+            .and_then(|txt| txt.slice(self.selections.primary().range()))
         {
             if !text.is_empty() {
                 Application::global().clipboard().put_string(text);
@@ -209,4 +780,660 @@ impl<T: TextStorage + EditableText> Editor<T> {
     pub fn paste(&mut self, t: String, data: &mut T) {
         self.do_edit(EditAction::Paste(t), data)
     }
+
+    /// Search the document for `query`, replacing any previous search.
+    /// The match cache is (re)built lazily, on the next call that needs it.
+    pub fn set_search(&mut self, query: Option<SearchQuery>) {
+        self.search = query;
+        self.matches_stale = true;
+        self.active_match = None;
+    }
+
+    /// Recompute `self.matches` against the current text if the cache has
+    /// been invalidated by a search change or an edit.
+    fn ensure_matches(&mut self) {
+        if !self.matches_stale {
+            return;
+        }
+        self.matches = match (&self.search, self.layout.text()) {
+            (Some(query), Some(text)) => query.find_all(text),
+            _ => Vec::new(),
+        };
+        self.matches_stale = false;
+        if self.active_match.map_or(true, |i| i >= self.matches.len()) {
+            self.active_match = None;
+        }
+    }
+
+    /// Move the primary selection to the next match after the caret, wrapping
+    /// around to the first match if necessary.
+    pub fn search_next(&mut self) {
+        self.ensure_matches();
+        if self.matches.is_empty() {
+            return;
+        }
+        let caret = self.selections.primary().end;
+        let next = self
+            .matches
+            .iter()
+            .position(|m| m.start >= caret)
+            .unwrap_or(0);
+        self.select_match(next);
+    }
+
+    /// Move the primary selection to the nearest match before the caret,
+    /// wrapping around to the last match if necessary.
+    pub fn search_prev(&mut self) {
+        self.ensure_matches();
+        if self.matches.is_empty() {
+            return;
+        }
+        let caret = self.selections.primary().end;
+        let prev = self
+            .matches
+            .iter()
+            .rposition(|m| m.end <= caret)
+            .unwrap_or(self.matches.len() - 1);
+        self.select_match(prev);
+    }
+
+    fn select_match(&mut self, index: usize) {
+        let range = self.matches[index].clone();
+        self.active_match = Some(index);
+        self.selections.set_single(Selection::new(range.start, range.end));
+        self.click_anchor = None;
+        self.break_undo_group();
+    }
+
+    /// One rect per match, paired with whether it's the currently active
+    /// match, for `TextBox2` to render with a distinct highlight.
+    pub fn match_rects(&mut self) -> Vec<(Rect, bool)> {
+        self.ensure_matches();
+        self.matches
+            .iter()
+            .enumerate()
+            .flat_map(|(i, range)| {
+                let is_active = self.active_match == Some(i);
+                self.layout
+                    .rects_for_range(range.clone())
+                    .into_iter()
+                    .map(move |rect| (rect, is_active))
+            })
+            .collect()
+    }
+}
+
+/// A search pattern for [`Editor::set_search`], either a plain substring or a
+/// simple pattern (`.` and `*` only, see [`SearchQuery::with_regex`]),
+/// matched against the document text.
+#[derive(Debug, Clone)]
+pub struct SearchQuery {
+    pattern: String,
+    regex: bool,
+    case_sensitive: bool,
+}
+
+impl SearchQuery {
+    pub fn new(pattern: impl Into<String>) -> Self {
+        SearchQuery {
+            pattern: pattern.into(),
+            regex: false,
+            case_sensitive: true,
+        }
+    }
+
+    /// Treat `pattern` as a pattern rather than a literal substring. Only `.`
+    /// (any char) and `*` (zero or more of the preceding atom) are special;
+    /// other regular-expression syntax (classes, anchors, groups,
+    /// alternation, escapes) is not supported and matches literally.
+    pub fn with_regex(mut self, regex: bool) -> Self {
+        self.regex = regex;
+        self
+    }
+
+    pub fn case_insensitive(mut self) -> Self {
+        self.case_sensitive = false;
+        self
+    }
+
+    /// All non-overlapping, grapheme-aligned byte ranges in `text` that match
+    /// this query. Matching itself works in chars (or Unicode scalar values),
+    /// so a match can land inside a multi-codepoint grapheme cluster (e.g. a
+    /// base letter plus a combining mark); any such range is widened outward
+    /// to the nearest grapheme boundaries before being returned, so selecting
+    /// a match always lands on valid boundaries.
+    fn find_all(&self, text: &impl EditableText) -> Vec<Range<usize>> {
+        if self.pattern.is_empty() {
+            return Vec::new();
+        }
+        let haystack = text.as_str();
+        let raw = if self.regex {
+            find_all_pattern(&self.pattern, haystack, self.case_sensitive)
+        } else {
+            find_all_literal(&self.pattern, haystack, self.case_sensitive)
+        };
+        snap_to_grapheme_boundaries(raw, text)
+    }
+}
+
+/// Widens each range's start backward, and end forward, to the nearest
+/// grapheme boundary in `text`, so a match that splits a grapheme cluster
+/// still selects the whole cluster.
+fn snap_to_grapheme_boundaries(
+    ranges: Vec<Range<usize>>,
+    text: &impl EditableText,
+) -> Vec<Range<usize>> {
+    if ranges.is_empty() {
+        return ranges;
+    }
+    let mut bounds = vec![0usize];
+    let mut pos = 0;
+    while let Some(next) = text.next_grapheme_offset(pos) {
+        bounds.push(next);
+        pos = next;
+    }
+    ranges
+        .into_iter()
+        .map(|r| {
+            let start_idx = bounds.partition_point(|&b| b <= r.start);
+            let start = bounds[start_idx - 1];
+            let end_idx = bounds.partition_point(|&b| b < r.end);
+            let end = bounds.get(end_idx).copied().unwrap_or(r.end);
+            start..end
+        })
+        .collect()
+}
+
+/// Every non-overlapping byte range in `haystack` equal to `pattern`. Compares
+/// character-by-character (folding case per-character when `case_sensitive`
+/// is false) instead of matching against a whole-string `.to_lowercase()`
+/// copy, so the returned ranges always index `haystack` itself, even where
+/// case-folding changes a character's UTF-8 length (e.g. `İ`).
+fn find_all_literal(pattern: &str, haystack: &str, case_sensitive: bool) -> Vec<Range<usize>> {
+    if case_sensitive {
+        return haystack
+            .match_indices(pattern)
+            .map(|(start, matched)| start..start + matched.len())
+            .collect();
+    }
+
+    let pat: Vec<char> = pattern.chars().collect();
+    let indices: Vec<(usize, char)> = haystack.char_indices().collect();
+
+    let mut matches = Vec::new();
+    let mut i = 0;
+    while i + pat.len() <= indices.len() {
+        let is_match = pat
+            .iter()
+            .enumerate()
+            .all(|(j, &p)| chars_eq(p, indices[i + j].1, false));
+        if is_match {
+            let start = indices[i].0;
+            let end = indices
+                .get(i + pat.len())
+                .map(|(byte, _)| *byte)
+                .unwrap_or(haystack.len());
+            matches.push(start..end);
+            i += pat.len().max(1);
+        } else {
+            i += 1;
+        }
+    }
+    matches
+}
+
+/// A minimal regex-style matcher supporting `.` (any char) and `*` (zero or
+/// more of the preceding atom), in the style of Kernighan & Pike's classic
+/// `match`. This tree has no `Cargo.toml` to add an external `regex`
+/// dependency to, so "regex mode" is this small built-in subset rather than
+/// a full engine.
+fn find_all_pattern(pattern: &str, haystack: &str, case_sensitive: bool) -> Vec<Range<usize>> {
+    let pat: Vec<char> = pattern.chars().collect();
+    let indices: Vec<(usize, char)> = haystack.char_indices().collect();
+
+    let mut matches = Vec::new();
+    let mut i = 0;
+    while i <= indices.len() {
+        let text: Vec<char> = indices[i..].iter().map(|(_, c)| *c).collect();
+        if let Some(len) = match_from(&text, &pat, case_sensitive) {
+            let start = indices.get(i).map(|(byte, _)| *byte).unwrap_or(haystack.len());
+            let end = indices
+                .get(i + len)
+                .map(|(byte, _)| *byte)
+                .unwrap_or(haystack.len());
+            matches.push(start..end);
+            i += len.max(1);
+        } else {
+            i += 1;
+        }
+    }
+    matches
+}
+
+/// Try to match `pat` against a prefix of `text`, returning the length (in
+/// chars) of the longest match, if any.
+fn match_from(text: &[char], pat: &[char], case_sensitive: bool) -> Option<usize> {
+    if pat.is_empty() {
+        return Some(0);
+    }
+    if pat.len() >= 2 && pat[1] == '*' {
+        let atom = pat[0];
+        let rest = &pat[2..];
+        let mut n = 0;
+        while n < text.len() && (atom == '.' || chars_eq(atom, text[n], case_sensitive)) {
+            n += 1;
+        }
+        loop {
+            if let Some(len) = match_from(&text[n..], rest, case_sensitive) {
+                return Some(n + len);
+            }
+            if n == 0 {
+                return None;
+            }
+            n -= 1;
+        }
+    }
+    match text.first() {
+        Some(&c) if pat[0] == '.' || chars_eq(pat[0], c, case_sensitive) => {
+            match_from(&text[1..], &pat[1..], case_sensitive).map(|l| l + 1)
+        }
+        _ => None,
+    }
+}
+
+/// Compares two chars for equality, case-folding both sides when
+/// `case_sensitive` is false. Folding happens per-character rather than by
+/// lowercasing a whole string up front, so callers can keep indexing the
+/// original, un-folded text.
+fn chars_eq(a: char, b: char, case_sensitive: bool) -> bool {
+    if case_sensitive {
+        a == b
+    } else {
+        a.to_lowercase().eq(b.to_lowercase())
+    }
+}
+
+type KeyChord = (KbKey, Modifiers);
+
+/// A data-driven [`TextInput`] that looks a key press up in an ordered table
+/// of `(KbKey, Modifiers) -> EditAction` bindings, falling through to default
+/// text insertion when nothing matches. Lets applications rebind keys (or
+/// layer a whole alternate scheme, e.g. Emacs-style bindings) without
+/// replacing `Editor`'s input handling entirely.
+///
+/// Bindings may also be chained into two-key sequences (e.g. a prefix key
+/// followed by a command key); the prefix consumes its keystroke and
+/// `KeymapInput` waits for the next one before dispatching.
+#[derive(Debug, Default)]
+pub struct KeymapInput {
+    bindings: Vec<(KeyChord, EditAction)>,
+    sequences: Vec<(KeyChord, KeyChord, EditAction)>,
+    fallback: BasicTextInput,
+    pending: Option<KeyChord>,
+}
+
+impl KeymapInput {
+    pub fn new() -> Self {
+        KeymapInput::default()
+    }
+
+    /// Bind a single key chord directly to `action`. Later bindings for the
+    /// same chord take priority over earlier ones.
+    pub fn bind(mut self, key: KbKey, mods: Modifiers, action: EditAction) -> Self {
+        self.bindings.push(((key, mods), action));
+        self
+    }
+
+    /// Bind a two-key sequence (`first` then `second`) to `action`, e.g.
+    /// Emacs-style `Ctrl+X` `Ctrl+S`.
+    pub fn bind_sequence(mut self, first: KeyChord, second: KeyChord, action: EditAction) -> Self {
+        self.sequences.push((first, second, action));
+        self
+    }
+
+    /// Emacs-style bindings layered over the default keymap: `Ctrl+A`/`Ctrl+E`
+    /// jump to the start/end of the line, and `Ctrl+K` deletes to the end of
+    /// the line.
+    pub fn emacs() -> Self {
+        KeymapInput::new()
+            .bind(
+                KbKey::Character("a".into()),
+                Modifiers::CONTROL,
+                EditAction::Move(Movement::LineStart),
+            )
+            .bind(
+                KbKey::Character("e".into()),
+                Modifiers::CONTROL,
+                EditAction::Move(Movement::LineEnd),
+            )
+            .bind(
+                KbKey::Character("k".into()),
+                Modifiers::CONTROL,
+                EditAction::JumpDelete(Movement::LineEnd),
+            )
+    }
+
+    fn lookup(&self, chord: &KeyChord) -> Option<EditAction> {
+        self.bindings
+            .iter()
+            .rev()
+            .find(|(bound, _)| bound == chord)
+            .map(|(_, action)| action.clone())
+    }
+
+    fn is_prefix(&self, chord: &KeyChord) -> bool {
+        self.sequences.iter().any(|(first, _, _)| first == chord)
+    }
+}
+
+impl TextInput for KeymapInput {
+    fn handle_event(&mut self, event: &KeyEvent) -> Option<EditAction> {
+        let chord = (event.key.clone(), event.mods);
+
+        if let Some(prefix) = self.pending.take() {
+            if let Some((_, _, action)) = self
+                .sequences
+                .iter()
+                .find(|(first, second, _)| *first == prefix && *second == chord)
+            {
+                return Some(action.clone());
+            }
+            // the second key didn't complete a known sequence; fall through
+            // and handle this key press on its own.
+        }
+
+        if self.is_prefix(&chord) {
+            self.pending = Some(chord);
+            return None;
+        }
+
+        self.lookup(&chord)
+            .or_else(|| self.fallback.handle_event(event))
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+/// The mode a [`ModalInput`] is currently in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputMode {
+    /// Keys are commands/motions; does not insert text.
+    Normal,
+    /// Keys insert text, as with [`BasicTextInput`].
+    Insert,
+    /// Like `Normal`, but motions extend the selection instead of moving the caret.
+    Visual,
+}
+
+/// An operator awaiting a motion to act on, e.g. the `d` in `dw`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ModalOperator {
+    Delete,
+    Change,
+    Yank,
+}
+
+/// A vi-style modal [`TextInput`]: `h`/`j`/`k`/`l` and `w`/`b`/`e` move by
+/// character and word, `0`/`$` jump to the line ends, `i`/`a`/`o` enter
+/// insert mode, `v` toggles visual (selecting) mode, and an operator
+/// (`d`/`c`/`y`) composed with a following motion deletes, changes, or yanks
+/// the range the motion covers.
+#[derive(Debug)]
+pub struct ModalInput {
+    mode: InputMode,
+    pending_operator: Option<ModalOperator>,
+    fallback: BasicTextInput,
+}
+
+impl Default for ModalInput {
+    fn default() -> Self {
+        ModalInput {
+            mode: InputMode::Normal,
+            pending_operator: None,
+            fallback: BasicTextInput::default(),
+        }
+    }
+}
+
+impl ModalInput {
+    pub fn new() -> Self {
+        ModalInput::default()
+    }
+
+    pub fn mode(&self) -> InputMode {
+        self.mode
+    }
+
+    fn motion_for_key(key: &KbKey) -> Option<Movement> {
+        match key {
+            KbKey::Character(c) => match c.as_str() {
+                "h" => Some(Movement::Left),
+                "l" => Some(Movement::Right),
+                "j" => Some(Movement::Down),
+                "k" => Some(Movement::Up),
+                "w" => Some(Movement::RightWord),
+                "b" => Some(Movement::LeftWord),
+                "e" => Some(Movement::RightWord),
+                "0" => Some(Movement::LineStart),
+                "$" => Some(Movement::LineEnd),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Handle a key press while in `Normal` or `Visual` mode.
+    fn handle_command_key(&mut self, event: &KeyEvent) -> Option<EditAction> {
+        if let KbKey::Character(ref c) = event.key {
+            match c.as_str() {
+                "i" => {
+                    self.mode = InputMode::Insert;
+                    self.pending_operator = None;
+                    return None;
+                }
+                "a" => {
+                    self.mode = InputMode::Insert;
+                    self.pending_operator = None;
+                    return Some(EditAction::Move(Movement::Right));
+                }
+                "o" => {
+                    // Inserts a newline at the caret and enters insert mode.
+                    // `ModalInput` doesn't have access to the document, so it
+                    // can't find the current line's end itself; this opens a
+                    // new line below only when the caret is already at the
+                    // end of the line. After `Escape` the caret sits on the
+                    // last-typed grapheme, so `o` then splits that grapheme
+                    // off onto the new line.
+                    self.mode = InputMode::Insert;
+                    self.pending_operator = None;
+                    return Some(EditAction::Insert("\n".to_string()));
+                }
+                "O" => {
+                    // Same caveat as `o`, mirrored: moves to the start of the
+                    // line and enters insert mode, but (lacking document
+                    // access here) doesn't push the rest of the line down
+                    // onto a fresh blank line above the caret itself.
+                    self.mode = InputMode::Insert;
+                    self.pending_operator = None;
+                    return Some(EditAction::Move(Movement::LineStart));
+                }
+                "v" => {
+                    self.mode = if self.mode == InputMode::Visual {
+                        InputMode::Normal
+                    } else {
+                        InputMode::Visual
+                    };
+                    self.pending_operator = None;
+                    return None;
+                }
+                "d" | "c" | "y" => {
+                    self.pending_operator = Some(match c.as_str() {
+                        "d" => ModalOperator::Delete,
+                        "c" => ModalOperator::Change,
+                        _ => ModalOperator::Yank,
+                    });
+                    return None;
+                }
+                _ => (),
+            }
+
+            if let Some(mvmt) = Self::motion_for_key(&event.key) {
+                if let Some(op) = self.pending_operator.take() {
+                    return Some(match op {
+                        ModalOperator::Delete => EditAction::JumpDelete(mvmt),
+                        ModalOperator::Change => {
+                            self.mode = InputMode::Insert;
+                            EditAction::JumpDelete(mvmt)
+                        }
+                        ModalOperator::Yank => EditAction::Yank(mvmt),
+                    });
+                }
+                return Some(if self.mode == InputMode::Visual {
+                    EditAction::ModifySelection(mvmt)
+                } else {
+                    EditAction::Move(mvmt)
+                });
+            }
+        }
+        self.pending_operator = None;
+        None
+    }
+}
+
+impl TextInput for ModalInput {
+    fn handle_event(&mut self, event: &KeyEvent) -> Option<EditAction> {
+        match self.mode {
+            InputMode::Insert => {
+                if event.key == KbKey::Escape {
+                    self.mode = InputMode::Normal;
+                    // as in vi, leaving insert mode steps the caret back onto
+                    // the last-typed grapheme rather than just past it.
+                    return Some(EditAction::Move(Movement::Left));
+                }
+                self.fallback.handle_event(event)
+            }
+            InputMode::Normal | InputMode::Visual => self.handle_command_key(event),
+        }
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn editor_with(text: &str) -> (Editor<String>, String) {
+        let data = text.to_string();
+        let mut editor = Editor::new().with_multi_line(true);
+        editor.set_text(data.clone());
+        (editor, data)
+    }
+
+    /// Keep `editor`'s cached text in sync with `data`, as `Editor::update`
+    /// would after each edit is applied to the real widget's data.
+    fn sync(editor: &mut Editor<String>, data: &String) {
+        editor.set_text(data.clone());
+    }
+
+    #[test]
+    fn coalesced_inserts_undo_and_redo_as_one_group() {
+        let (mut editor, mut data) = editor_with("");
+        for ch in ["h", "i"] {
+            editor.do_edit(EditAction::Insert(ch.to_string()), &mut data);
+            sync(&mut editor, &data);
+        }
+        assert_eq!(data, "hi");
+
+        editor.do_edit(EditAction::Undo, &mut data);
+        sync(&mut editor, &data);
+        assert_eq!(data, "", "coalesced single-char inserts should undo together");
+
+        editor.do_edit(EditAction::Redo, &mut data);
+        sync(&mut editor, &data);
+        assert_eq!(data, "hi");
+    }
+
+    #[test]
+    fn insert_then_backspace_are_separate_undo_groups() {
+        let (mut editor, mut data) = editor_with("");
+        editor.do_edit(EditAction::Insert("a".into()), &mut data);
+        sync(&mut editor, &data);
+        editor.do_edit(EditAction::Backspace, &mut data);
+        sync(&mut editor, &data);
+        assert_eq!(data, "");
+
+        // an insert and a backspace are different `GroupKind`s, so even though
+        // both are single-caret single-character edits, they must not
+        // coalesce into one undo transaction.
+        editor.do_edit(EditAction::Undo, &mut data);
+        sync(&mut editor, &data);
+        assert_eq!(data, "a", "undo should only reverse the backspace");
+
+        editor.do_edit(EditAction::Undo, &mut data);
+        sync(&mut editor, &data);
+        assert_eq!(data, "", "a second undo should reverse the insert");
+    }
+
+    #[test]
+    fn multi_cursor_insert_shifts_later_selections() {
+        let (mut editor, mut data) = editor_with("aaaa");
+        editor.selections = SelectionSet {
+            ranges: vec![Selection::caret(1), Selection::caret(3)],
+            affinities: vec![None, None],
+            primary: 0,
+        };
+
+        editor.do_edit(EditAction::Insert("X".into()), &mut data);
+        sync(&mut editor, &data);
+
+        assert_eq!(data, "aXaaXa");
+        assert_eq!(
+            editor.selections(),
+            vec![Selection::caret(2), Selection::caret(5)],
+            "the second caret's offset must shift by the first edit's length delta"
+        );
+    }
+
+    #[test]
+    fn undo_redo_interleaved_with_add_caret_at_next_occurrence() {
+        let (mut editor, mut data) = editor_with("foo foo");
+        editor.selections = SelectionSet {
+            ranges: vec![Selection::new(0, 3)],
+            affinities: vec![None],
+            primary: 0,
+        };
+
+        editor.do_edit(EditAction::AddCaretAtNextOccurrence, &mut data);
+        assert_eq!(
+            editor.selections(),
+            vec![Selection::new(0, 3), Selection::new(4, 7)],
+            "should have selected both occurrences of \"foo\""
+        );
+
+        editor.do_edit(EditAction::Insert("X".into()), &mut data);
+        sync(&mut editor, &data);
+        assert_eq!(data, "X X");
+
+        editor.do_edit(EditAction::Undo, &mut data);
+        sync(&mut editor, &data);
+        assert_eq!(data, "foo foo", "undo should restore both replaced occurrences");
+        assert_eq!(
+            editor.selections(),
+            vec![Selection::new(0, 3), Selection::new(4, 7)],
+            "undo should restore the selections that were active before the edit"
+        );
+
+        editor.do_edit(EditAction::Redo, &mut data);
+        sync(&mut editor, &data);
+        assert_eq!(data, "X X");
+        assert_eq!(
+            editor.selections(),
+            vec![Selection::caret(1), Selection::caret(3)]
+        );
+    }
 }
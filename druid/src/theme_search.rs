@@ -0,0 +1,27 @@
+// Copyright 2021 The Druid Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Theme keys for `Editor`/`TextBox2`'s incremental search highlighting.
+//!
+//! These live in their own module rather than `theme.rs` because this trimmed
+//! checkout doesn't carry that file; re-export them from `theme` alongside
+//! the rest of the built-in keys once this lands next to it.
+
+use crate::{Color, Key};
+
+/// Fill color for a non-active search match.
+pub const SEARCH_MATCH_COLOR: Key<Color> = Key::new("druid.search-match-color");
+
+/// Fill color for the currently active search match.
+pub const SEARCH_ACTIVE_MATCH_COLOR: Key<Color> = Key::new("druid.search-active-match-color");
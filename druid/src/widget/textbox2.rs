@@ -14,7 +14,7 @@
 
 //! A textbox widget.
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::widget::prelude::*;
 use crate::{
@@ -23,8 +23,9 @@ use crate::{
 };
 
 use crate::theme;
+use crate::theme_search::{SEARCH_ACTIVE_MATCH_COLOR, SEARCH_MATCH_COLOR};
 
-use crate::text::{EditAction, EditableText, Editor, TextStorage};
+use crate::text::{EditAction, EditableText, Editor, InputMode, SearchQuery, TextStorage};
 
 //const BORDER_WIDTH: f64 = 1.;
 //const TEXT_INSETS: Insets = Insets::new(4.0, 2.0, 0.0, 2.0);
@@ -33,19 +34,42 @@ use crate::text::{EditAction, EditableText, Editor, TextStorage};
 const RESET_BLINK: Selector = Selector::new("druid-builtin.reset-textbox-blink");
 const CURSOR_BLINK_DURATION: Duration = Duration::from_millis(500);
 
+/// The maximum gap between two mouse-downs, and the maximum distance between
+/// them, for them to count as part of the same double/triple-click sequence.
+const MULTI_CLICK_INTERVAL: Duration = Duration::from_millis(400);
+const MULTI_CLICK_MAX_DISTANCE: f64 = 5.0;
+
 /// A widget that allows user text input.
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct TextBox2<T> {
     //placeholder: String,
     editor: Editor<T>,
     cursor_timer: TimerToken,
     cursor_on: bool,
+    last_click: Option<(Point, Instant, u32)>,
+    last_mode: Option<InputMode>,
 }
 
 impl TextBox2<()> {
     /// Perform an `EditAction`. The payload *must* be an `EditAction`.
     pub const PERFORM_EDIT: Selector<EditAction> =
         Selector::new("druid-builtin.textbox.perform-edit");
+
+    /// Sent when a modal (vi-style) input's mode changes, so the surrounding
+    /// app can reflect it in e.g. a status line. The payload is the new `InputMode`.
+    pub const MODE_CHANGED: Selector<InputMode> =
+        Selector::new("druid-builtin.textbox.mode-changed");
+
+    /// Set (or clear, with `None`) the active incremental search, e.g. driven
+    /// by a find bar elsewhere in the app.
+    pub const SET_SEARCH: Selector<Option<SearchQuery>> =
+        Selector::new("druid-builtin.textbox.set-search");
+
+    /// Move to the next search match after the caret, wrapping around.
+    pub const SEARCH_NEXT: Selector = Selector::new("druid-builtin.textbox.search-next");
+
+    /// Move to the nearest search match before the caret, wrapping around.
+    pub const SEARCH_PREV: Selector = Selector::new("druid-builtin.textbox.search-prev");
 }
 
 impl<T> TextBox2<T> {
@@ -55,6 +79,8 @@ impl<T> TextBox2<T> {
             editor: Editor::new().with_multi_line(true),
             cursor_timer: TimerToken::INVALID,
             cursor_on: false,
+            last_click: None,
+            last_mode: None,
         }
     }
 
@@ -62,6 +88,23 @@ impl<T> TextBox2<T> {
         self.cursor_on = true;
         self.cursor_timer = ctx.request_timer(CURSOR_BLINK_DURATION);
     }
+
+    /// 1 for a plain click, 2 for a double-click, 3+ for a triple-click (and
+    /// beyond), based on the timing and position of the previous mouse-down.
+    fn click_count(&mut self, mouse: &MouseEvent) -> u32 {
+        let now = Instant::now();
+        let count = match self.last_click {
+            Some((pos, at, count))
+                if now.duration_since(at) < MULTI_CLICK_INTERVAL
+                    && pos.distance(mouse.pos) <= MULTI_CLICK_MAX_DISTANCE =>
+            {
+                count + 1
+            }
+            _ => 1,
+        };
+        self.last_click = Some((mouse.pos, now, count));
+        count
+    }
 }
 
 impl<T: TextStorage + EditableText> Widget<T> for TextBox2<T> {
@@ -73,7 +116,8 @@ impl<T: TextStorage + EditableText> Widget<T> for TextBox2<T> {
 
                 if !mouse.focus {
                     self.reset_cursor_blink(ctx);
-                    self.editor.click(mouse, data);
+                    let click_count = self.click_count(mouse);
+                    self.editor.click(mouse, click_count, data);
                 }
 
                 ctx.request_paint();
@@ -111,6 +155,19 @@ impl<T: TextStorage + EditableText> Widget<T> for TextBox2<T> {
                 let edit = cmd.get_unchecked(TextBox2::PERFORM_EDIT);
                 self.editor.do_edit(edit.to_owned(), data);
             }
+            Event::Command(cmd) if cmd.is(TextBox2::SET_SEARCH) => {
+                let query = cmd.get_unchecked(TextBox2::SET_SEARCH);
+                self.editor.set_search(query.to_owned());
+                ctx.request_paint();
+            }
+            Event::Command(cmd) if cmd.is(TextBox2::SEARCH_NEXT) => {
+                self.editor.search_next();
+                ctx.request_paint();
+            }
+            Event::Command(cmd) if cmd.is(TextBox2::SEARCH_PREV) => {
+                self.editor.search_prev();
+                ctx.request_paint();
+            }
             Event::Paste(ref item) => {
                 if let Some(string) = item.get_string() {
                     self.editor.paste(string.to_owned(), data);
@@ -121,10 +178,35 @@ impl<T: TextStorage + EditableText> Widget<T> for TextBox2<T> {
                     // Tab and shift+tab
                     k_e if HotKey::new(None, KbKey::Tab).matches(k_e) => ctx.focus_next(),
                     k_e if HotKey::new(SysMods::Shift, KbKey::Tab).matches(k_e) => ctx.focus_prev(),
+                    // undo / redo
+                    k_e if HotKey::new(SysMods::Cmd, "z").matches(k_e) => {
+                        self.editor.do_edit(EditAction::Undo, data)
+                    }
+                    k_e if HotKey::new(SysMods::CmdShift, "z").matches(k_e) => {
+                        self.editor.do_edit(EditAction::Redo, data)
+                    }
+                    // multiple cursors
+                    k_e if HotKey::new(SysMods::AltCmd, KbKey::ArrowUp).matches(k_e) => {
+                        self.editor.do_edit(EditAction::AddCaretAbove, data)
+                    }
+                    k_e if HotKey::new(SysMods::AltCmd, KbKey::ArrowDown).matches(k_e) => {
+                        self.editor.do_edit(EditAction::AddCaretBelow, data)
+                    }
+                    k_e if HotKey::new(SysMods::Cmd, "d").matches(k_e) => {
+                        self.editor.do_edit(EditAction::AddCaretAtNextOccurrence, data)
+                    }
                     _ => self.editor.key(key_event, data),
                 };
                 self.reset_cursor_blink(ctx);
 
+                let mode = self.editor.modal_mode();
+                if mode != self.last_mode {
+                    self.last_mode = mode;
+                    if let Some(mode) = mode {
+                        ctx.submit_command(TextBox2::MODE_CHANGED.with(mode).to(ctx.widget_id()));
+                    }
+                }
+
                 ctx.request_paint();
             }
             _ => (),
@@ -160,6 +242,8 @@ impl<T: TextStorage + EditableText> Widget<T> for TextBox2<T> {
         let background_color = env.get(theme::BACKGROUND_LIGHT);
         let selection_color = env.get(theme::SELECTION_COLOR);
         let cursor_color = env.get(theme::CURSOR_COLOR);
+        let search_match_color = env.get(SEARCH_MATCH_COLOR);
+        let search_active_match_color = env.get(SEARCH_ACTIVE_MATCH_COLOR);
         let is_focused = ctx.is_focused();
 
         let rect = ctx.size().to_rect();
@@ -172,12 +256,31 @@ impl<T: TextStorage + EditableText> Widget<T> for TextBox2<T> {
         for rect in self.editor.selection_rects() {
             ctx.fill(rect, &selection_color);
         }
+        for (rect, is_active) in self.editor.match_rects() {
+            let color = if is_active {
+                &search_active_match_color
+            } else {
+                &search_match_color
+            };
+            ctx.fill(rect, color);
+        }
         self.editor.draw(ctx, Point::ORIGIN);
 
-        // Paint the cursor if focused and there's no selection
+        // Paint the cursor(s) if focused, whether or not there's a selection
         if is_focused && self.cursor_on {
-            let line = self.editor.cursor_line();
-            ctx.stroke(line, &cursor_color, 1.);
+            match self.editor.modal_mode() {
+                // block caret in normal/visual mode, as in vi-style editors
+                Some(InputMode::Normal) | Some(InputMode::Visual) => {
+                    for block in self.editor.cursor_blocks() {
+                        ctx.fill(block, &cursor_color);
+                    }
+                }
+                Some(InputMode::Insert) | None => {
+                    for line in self.editor.cursor_lines() {
+                        ctx.stroke(line, &cursor_color, 1.);
+                    }
+                }
+            }
         }
     }
 }